@@ -29,12 +29,19 @@
 
 use std::ffi::CString;
 use std::fs::File;
-use std::mem::MaybeUninit;
+use std::io;
+use std::io::Write as _;
+use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
+use std::thread;
 
-use constellation_common::sync::Notify;
+use clap::Arg;
+use clap::ArgAction;
+use clap::ArgMatches;
+use clap::Command;
 use libc::c_int;
+use libc::c_void;
 use libc::sighandler_t;
 use libc::signal;
 use libc::strerror;
@@ -46,14 +53,70 @@ use log::error;
 use log::info;
 use log::trace;
 use log::LevelFilter;
+use log::Record;
 use log4rs::append::console::ConsoleAppender;
-use log4rs::config::load_config_file;
+use log4rs::append::console::Target;
+use log4rs::append::file::FileAppender;
 use log4rs::config::Appender;
 use log4rs::config::Deserializers;
+use log4rs::config::RawConfig;
 use log4rs::config::Root;
+use log4rs::encode::Encode;
+use log4rs::encode::Write as EncodeWrite;
 use log4rs::Config;
 use log4rs::Handle;
 use serde::Deserialize;
+use serde_yaml::Mapping;
+use serde_yaml::Value;
+
+/// How to treat a log target file that already exists.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IfExists {
+    /// Fail rather than open a file that already exists.
+    Fail,
+    /// Truncate an existing file.
+    Truncate,
+    /// Append to an existing file.
+    Append
+}
+
+/// A built-in logging configuration, recognized directly by
+/// [Standalone::log_setup] without requiring a separate `log4rs` YAML
+/// file.
+///
+/// A component that wants sane structured logging out of the box can
+/// embed this in its `Config` and return it from
+/// [logging_config](Standalone::logging_config); `log_setup` will
+/// synthesize the corresponding `log4rs` configuration
+/// programmatically, Dropshot-style.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum LoggingConfig {
+    /// Log to the terminal's standard error.
+    StderrTerminal {
+        /// Minimum level to log.
+        level: LevelFilter
+    },
+    /// Log to a plain-text file.
+    File {
+        /// Minimum level to log.
+        level: LevelFilter,
+        /// Path of the log file.
+        path: PathBuf,
+        /// What to do if `path` already exists.
+        if_exists: IfExists
+    },
+    /// Log to a file as Bunyan-style JSON lines.
+    Json {
+        /// Minimum level to log.
+        level: LevelFilter,
+        /// Path of the log file.
+        path: PathBuf,
+        /// What to do if `path` already exists.
+        if_exists: IfExists
+    }
+}
 
 /// Trait to be implemented by standalone components.
 ///
@@ -65,7 +128,8 @@ use serde::Deserialize;
 ///
 /// * Setting up logging.
 ///
-/// * Setting up signal handlers to trigger shutdown.
+/// * Setting up signal handlers to trigger shutdown and configuration
+///   reloads.
 ///
 /// * Setting up and running the component.
 ///
@@ -126,7 +190,7 @@ pub trait Standalone: Sized {
     ) -> Result<(Self, Self::CreateCleanup), Self::CreateCleanup>;
 
     /// Entrypoint for the component.
-    fn run(self) -> Result<Self::RunCleanup, Self::RunErrorCleanup>;
+    fn run(&self) -> Result<Self::RunCleanup, Self::RunErrorCleanup>;
 
     /// Shut down the component and clean up any resources.
     ///
@@ -149,6 +213,43 @@ pub trait Standalone: Sized {
         run: Self::RunErrorCleanup
     );
 
+    /// Reload the component's configuration.
+    ///
+    /// This is called in response to `SIGHUP`, after the main
+    /// configuration and logging have been re-read, and is given the
+    /// freshly-loaded configuration.  Unlike shutdown, reloading does
+    /// not tear down the component; implementations should apply
+    /// whatever changes they can in place.
+    ///
+    /// The default implementation does nothing.
+    fn reload(
+        &self,
+        _config: Self::Config
+    ) {
+    }
+
+    /// Register any component-specific command-line arguments.
+    ///
+    /// The default implementation adds none.  Components that need
+    /// their own flags should add them to `cmd` and return it; they
+    /// can then read the parsed values back out in
+    /// [handle_args](Standalone::handle_args).
+    fn extra_args(cmd: Command) -> Command {
+        cmd
+    }
+
+    /// Apply component-specific command-line arguments parsed from
+    /// [extra_args](Standalone::extra_args).
+    ///
+    /// Called once, after [create](Standalone::create) and before
+    /// [run](Standalone::run).  The default implementation does
+    /// nothing.
+    fn handle_args(
+        &mut self,
+        _matches: &ArgMatches
+    ) {
+    }
+
     /// Get the set of configuration directories to search for
     /// configuration files.
     fn config_dirs() -> Vec<PathBuf> {
@@ -173,8 +274,9 @@ pub trait Standalone: Sized {
 
         // Compute component-specific configuration variable name.
         let component_env_name = format!(
-            "CONSTELLATION_{}_CONF_DIR",
-            Self::COMPONENT_NAME.to_uppercase()
+            "CONSTELLATION_{}_{}",
+            Self::COMPONENT_NAME.to_uppercase(),
+            CONF_DIR_ENV_SUFFIX
         );
 
         trace!(target: "standalone",
@@ -209,118 +311,364 @@ pub trait Standalone: Sized {
         out
     }
 
-    /// Set up the permanent logger.
-    fn log_setup(
+    /// Collect the configuration layer found in each of `dirs`, in
+    /// directory order.
+    ///
+    /// Within a single directory, the first name in `names` that
+    /// exists is that directory's layer.  If more than one of
+    /// `names` exists in the *same* directory, the source is
+    /// ambiguous: following jj's diagnostic, an error is logged
+    /// naming all of the conflicting paths, and the first (by
+    /// preference order) is still used rather than silently picking
+    /// one without comment.  Directories with no match contribute no
+    /// layer.
+    fn collect_config_layers<'a, I>(
         dirs: &[PathBuf],
-        handle: &Handle
-    ) {
-        debug!(target: "log-setup",
-               "loading permanent logging configuration");
+        names: I
+    ) -> Vec<PathBuf>
+    where
+        I: Iterator<Item = &'a str> + Clone {
+        let mut out = Vec::with_capacity(dirs.len());
 
-        // Use configuration to set up the permanent logger.
-        for file in Self::LOG_CONFIG_FILES {
-            debug!(target: "log-setup",
-                   "looking for logging configuration file {}",
-                   file);
+        for dir in dirs.iter() {
+            let mut found = Vec::new();
 
-            for dir in dirs.iter() {
-                let path = dir.join(file);
+            for name in names.clone() {
+                let path = dir.join(name);
 
-                trace!(target: "log-setup",
+                trace!(target: "load-config",
                        "trying path {}",
                        path.to_string_lossy());
 
                 if path.is_file() {
-                    debug!(target: "log-setup",
-                           "loading log config file {}",
+                    found.push(path);
+                } else {
+                    trace!(target: "load-config",
+                           "file {} not found",
                            path.to_string_lossy());
+                }
+            }
 
-                    match load_config_file(path.clone(), Deserializers::new()) {
-                        Ok(config) => {
-                            debug!(target: "log-setup",
-                                   "found valid logging configuration");
+            if found.len() > 1 {
+                let paths = found
+                    .iter()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                error!(target: "load-config",
+                       "ambiguous configuration source in {}: {}",
+                       dir.to_string_lossy(),
+                       paths);
+            }
 
-                            handle.set_config(config);
+            if let Some(path) = found.into_iter().next() {
+                out.push(path);
+            }
+        }
 
-                            debug!(target: "log-setup",
-                                   "permanent logger initialized");
+        out
+    }
 
-                            return;
-                        }
+    /// Extract a built-in [LoggingConfig] from the main
+    /// configuration, if the component carries one.
+    ///
+    /// The default implementation carries none, in which case
+    /// [log_setup](Standalone::log_setup) falls back to an explicit
+    /// `constellation-log.yaml`-style file, or the bootstrap logger
+    /// if none is found.  A component whose `Config` embeds a
+    /// [LoggingConfig] field should override this to return it.
+    fn logging_config(_config: &Self::Config) -> Option<LoggingConfig> {
+        None
+    }
+
+    /// Set up the permanent logger.
+    ///
+    /// An explicit logging configuration file always takes
+    /// precedence: every matching layer named by
+    /// [LOG_CONFIG_FILES](Standalone::LOG_CONFIG_FILES) across
+    /// [config_dirs](Standalone::config_dirs) is deep-merged
+    /// together, home overriding system, and built into a
+    /// [log4rs::Config].  Failing that, if the main configuration
+    /// carries a built-in [LoggingConfig] (see
+    /// [logging_config](Standalone::logging_config)), that is
+    /// synthesized into a `log4rs::Config` programmatically.
+    /// Otherwise, the bootstrap logger is kept.
+    ///
+    /// `level_override`, when present, forces the root log level
+    /// regardless of what any file or built-in mode specified; it is
+    /// used to apply `-v`/`-q` from the command line.
+    ///
+    /// `reload` is `true` when this call is reconfiguring logging in
+    /// response to a `SIGHUP` rather than setting it up for the
+    /// first time; it is passed on to
+    /// [build_logging_config] so that a built-in [File](LoggingConfig::File)
+    /// or [Json](LoggingConfig::Json) target already opened on a
+    /// previous call doesn't get re-validated against
+    /// [IfExists::Fail] or truncated again by [IfExists::Truncate].
+    fn log_setup(
+        dirs: &[PathBuf],
+        handle: &Handle,
+        config: Option<&Self::Config>,
+        level_override: Option<LevelFilter>,
+        reload: bool
+    ) {
+        debug!(target: "log-setup",
+               "loading permanent logging configuration");
+
+        let layers = Self::collect_config_layers(
+            dirs,
+            Self::LOG_CONFIG_FILES.iter().copied()
+        );
+
+        if !layers.is_empty() {
+            let mut value = Value::Mapping(Mapping::new());
+
+            for path in layers.iter().rev() {
+                debug!(target: "log-setup",
+                       "merging logging configuration layer {}",
+                       path.to_string_lossy());
+
+                match File::open(path) {
+                    Ok(file) => match serde_yaml::from_reader(file) {
+                        Ok(layer) => merge_config_values(&mut value, layer),
                         Err(err) => {
                             error!(target: "log-setup",
-                                   "error loading config file: {}", err);
+                                   "error parsing logging configuration at \
+                                    {}: {}",
+                                   path.to_string_lossy(), err);
                         }
+                    },
+                    Err(err) => {
+                        error!(target: "log-setup",
+                               "error loading file {}: {}",
+                               path.to_string_lossy(), err)
                     }
-                } else {
-                    trace!(target: "log-setup",
-                           "file {} not found",
-                           path.to_string_lossy());
                 }
             }
+
+            if let Some(level) = level_override {
+                set_config_path(
+                    &mut value,
+                    &["root".to_string(), "level".to_string()],
+                    Value::String(level.to_string())
+                );
+            }
+
+            return match serde_yaml::from_value::<RawConfig>(value) {
+                Ok(raw) => {
+                    let (config, errs) = raw.build(Deserializers::new());
+
+                    for err in errs {
+                        error!(target: "log-setup",
+                               "error building logging configuration: {}",
+                               err);
+                    }
+
+                    handle.set_config(config);
+
+                    debug!(target: "log-setup",
+                           "permanent logger initialized");
+                }
+                Err(err) => {
+                    error!(target: "log-setup",
+                           "error parsing merged logging configuration: {}",
+                           err);
+
+                    debug!(target: "log-setup",
+                           "keeping bootstrap logger");
+                }
+            };
+        }
+
+        if let Some(logging) = config.and_then(Self::logging_config) {
+            debug!(target: "log-setup",
+                   "synthesizing built-in logging configuration");
+
+            match build_logging_config(logging, level_override, reload) {
+                Ok(log_config) => {
+                    handle.set_config(log_config);
+
+                    debug!(target: "log-setup",
+                           "permanent logger initialized");
+                }
+                Err(err) => {
+                    error!(target: "log-setup",
+                           "error building logging configuration: {}",
+                           err);
+
+                    debug!(target: "log-setup",
+                           "keeping bootstrap logger");
+                }
+            }
+
+            return;
         }
 
         debug!(target: "log-setup",
                "keeping bootstrap logger");
     }
 
+    /// Apply environment-variable overrides on top of a parsed
+    /// configuration tree.
+    ///
+    /// Borrowing Rocket's `ROCKET_{PARAM}` convention, any
+    /// environment variable named
+    /// `CONSTELLATION_{COMPONENT_NAME}_{PATH}` overrides the
+    /// corresponding field of `value`.  `PATH` is the field path,
+    /// lower-cased, with a double underscore (`__`) separating
+    /// nested keys so that a single underscore can still appear
+    /// within a field name (e.g. `CONSTELLATION_GROUP_LISTEN_PORT`
+    /// overrides the top-level `listen_port` field of the `group`
+    /// component).  Each override's value is itself parsed as a YAML
+    /// scalar, so numbers and booleans come through as their proper
+    /// type rather than as strings.
+    ///
+    /// `PATH` equal to exactly
+    /// [CONF_DIR_ENV_SUFFIX] is skipped, since that name is already
+    /// used by [config_dirs](Standalone::config_dirs) to select a
+    /// configuration directory rather than override a config field.
+    fn apply_env_overrides(value: &mut Value) {
+        let prefix = format!(
+            "CONSTELLATION_{}_",
+            Self::COMPONENT_NAME.to_uppercase()
+        );
+
+        for (key, val) in std::env::vars() {
+            if let Some(segments) = env_override_segments(&prefix, &key) {
+                debug!(target: "load-config",
+                       "applying environment override {}",
+                       key);
+
+                let scalar = serde_yaml::from_str(&val)
+                    .unwrap_or_else(|_| Value::String(val.clone()));
+
+                set_config_path(value, &segments, scalar);
+            }
+        }
+    }
+
+    /// Load a single, explicitly-named configuration file, bypassing
+    /// [config_dirs](Standalone::config_dirs) and
+    /// [CONFIG_FILES](Standalone::CONFIG_FILES) entirely.
+    ///
+    /// Used for the `--config` command-line option.  The file is
+    /// still merged with any `CONSTELLATION_{COMPONENT_NAME}_*`
+    /// environment overrides (see
+    /// [apply_env_overrides](Standalone::apply_env_overrides)).
+    fn load_config_file(path: &Path) -> Option<Self::Config> {
+        debug!(target: "load-config",
+               "loading explicit configuration file {}",
+               path.to_string_lossy());
+
+        let mut value = match File::open(path) {
+            Ok(file) => match serde_yaml::from_reader(file) {
+                Ok(value) => value,
+                Err(err) => {
+                    error!(target: "load-config",
+                           "error parsing configuration at {}: {}",
+                           path.to_string_lossy(), err);
+
+                    return None;
+                }
+            },
+            Err(err) => {
+                error!(target: "load-config",
+                       "error loading file {}: {}",
+                       path.to_string_lossy(), err);
+
+                return None;
+            }
+        };
+
+        Self::apply_env_overrides(&mut value);
+
+        match serde_yaml::from_value(value) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                error!(target: "load-config",
+                       "error parsing configuration: {}",
+                       err);
+
+                None
+            }
+        }
+    }
+
     /// Load a configuration file from a set of paths, and a set of
     /// possible names.
+    ///
+    /// Every matching layer across `dirs` is deep-merged together,
+    /// home overriding system (see
+    /// [collect_config_layers](Standalone::collect_config_layers)),
+    /// then merged with any `CONSTELLATION_{COMPONENT_NAME}_*`
+    /// environment overrides (see
+    /// [apply_env_overrides](Standalone::apply_env_overrides)) before
+    /// being deserialized into [Config](Standalone::Config).  A
+    /// missing configuration file is not an error so long as the
+    /// remaining layers and the environment supply enough to produce
+    /// a valid configuration.
     fn load_config<'a, I>(
         dirs: &[PathBuf],
         names: I
     ) -> Option<Self::Config>
     where
-        I: Iterator<Item = &'a str> {
+        I: Iterator<Item = &'a str> + Clone {
         debug!(target: "load-config",
                "loading main configuration");
 
-        for file in names {
-            debug!(target: "load-config",
-                   "looking for main configuration file {}",
-                   file);
-
-            for dir in dirs.iter() {
-                let path = dir.join(file);
+        let layers = Self::collect_config_layers(dirs, names);
+        let mut value = Value::Mapping(Mapping::new());
 
-                trace!(target: "load-config",
-                       "trying path {}",
-                       path.to_string_lossy());
+        for path in layers.iter().rev() {
+            debug!(target: "load-config",
+                   "merging configuration layer {}",
+                   path.to_string_lossy());
+
+            match File::open(path) {
+                Ok(file) => match serde_yaml::from_reader(file) {
+                    Ok(layer) => merge_config_values(&mut value, layer),
+                    Err(err) => {
+                        error!(target: "load-config",
+                               "error parsing configuration at {}: {}",
+                               path.to_string_lossy(), err);
+                    }
+                },
+                Err(err) => {
+                    error!(target: "load-config",
+                           "error loading file {}: {}",
+                           path.to_string_lossy(), err)
+                }
+            }
+        }
 
-                if path.is_file() {
-                    debug!(target: "loag-config",
-                           "loading config file {}",
-                           path.to_string_lossy());
+        Self::apply_env_overrides(&mut value);
 
-                    match File::open(path.clone()) {
-                        Ok(file) => match serde_yaml::from_reader(file) {
-                            Ok(yaml) => {
-                                trace!(target: "load-config",
-                                       "found valid configuration");
+        match serde_yaml::from_value(value) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                error!(target: "load-config",
+                       "error parsing merged configuration: {}",
+                       err);
 
-                                return Some(yaml);
-                            }
-                            Err(err) => {
-                                error!(target: "load-config",
-                                       "error parsing configuration at {}: {}",
-                                       path.to_string_lossy(), err);
-                            }
-                        },
-                        Err(err) => {
-                            error!(target: "load-config",
-                                   "error loading file: {}",
-                                   err)
-                        }
-                    };
-                } else {
-                    trace!(target: "load-config",
-                           "file {} not found",
-                           path.to_string_lossy());
-                }
+                None
             }
         }
+    }
 
-        None
+    /// Resolve the main configuration, honoring an explicit
+    /// `--config` path (see
+    /// [load_config_file](Standalone::load_config_file)) if one was
+    /// given, and falling back to the usual search of `dirs` for
+    /// [CONFIG_FILES](Standalone::CONFIG_FILES) otherwise.
+    fn resolve_config(
+        dirs: &[PathBuf],
+        config_path: Option<&Path>
+    ) -> Option<Self::Config> {
+        match config_path {
+            Some(path) => Self::load_config_file(path),
+            None => Self::load_config(dirs, Self::CONFIG_FILES.iter().copied())
+        }
     }
 
     /// A complete `main` function implementation for a standalone
@@ -329,25 +677,93 @@ pub trait Standalone: Sized {
     /// This can be called from the executable `main` as its only
     /// content.
     fn main() {
+        // Parse command-line arguments first: `--config`,
+        // `--config-dir`, and `-v`/`-q` all influence how
+        // configuration and logging are set up below.
+        let cmd = Command::new(Self::COMPONENT_NAME)
+            .arg(
+                Arg::new("config")
+                    .long("config")
+                    .value_name("PATH")
+                    .help(
+                        "Load configuration from this file, bypassing the \
+                         usual search path"
+                    )
+            )
+            .arg(
+                Arg::new("config-dir")
+                    .long("config-dir")
+                    .value_name("DIR")
+                    .action(ArgAction::Append)
+                    .help(
+                        "Prepend this directory to the configuration \
+                         search path"
+                    )
+            )
+            .arg(
+                Arg::new("verbose")
+                    .short('v')
+                    .long("verbose")
+                    .action(ArgAction::Count)
+                    .help("Increase logging verbosity (may be repeated)")
+            )
+            .arg(
+                Arg::new("quiet")
+                    .short('q')
+                    .long("quiet")
+                    .action(ArgAction::SetTrue)
+                    .help("Decrease logging verbosity")
+            );
+
+        let matches = Self::extra_args(cmd).get_matches();
+        let level_override = cli_level_override(&matches);
+
         // First set up the bootstrap logger.
-        let handle = bootstrap_log_setup();
+        let handle =
+            bootstrap_log_setup(level_override.unwrap_or(LevelFilter::Trace));
+
+        let config_path =
+            matches.get_one::<String>("config").map(PathBuf::from);
+
+        // Get the configuration directories, honoring `--config-dir`.
+        let mut dirs = Self::config_dirs();
+
+        if let Some(extra_dirs) = matches.get_many::<String>("config-dir") {
+            for (index, dir) in extra_dirs.enumerate() {
+                dirs.insert(index, PathBuf::from(dir));
+            }
+        }
 
-        // Get the configuration directories.
-        let dirs = Self::config_dirs();
+        // Load the main configuration first, so that a built-in
+        // logging section in it can be honored.
+        let config = Self::resolve_config(&dirs, config_path.as_deref());
 
         // Set up the permanent logger.
-        Self::log_setup(&dirs, &handle);
+        Self::log_setup(&dirs, &handle, config.as_ref(), level_override, false);
 
-        if let Some(config) =
-            Self::load_config(&dirs, Self::CONFIG_FILES.iter().copied())
-        {
+        if let Some(config) = config {
             match Self::create(config) {
-                Ok((app, create_cleanup)) => {
-                    // Register signal handlers.
+                Ok((mut app, create_cleanup)) => {
+                    app.handle_args(&matches);
+
+                    // Set up the self-pipe and register signal
+                    // handlers; the handlers themselves do nothing
+                    // but write the signal number to the pipe, so
+                    // all the real work happens here on the main
+                    // thread instead of in async-signal-unsafe
+                    // handler context.
+
+                    let read_fd = match setup_signal_pipe() {
+                        Ok(read_fd) => read_fd,
+                        Err(err) => {
+                            error!(target: "standalone",
+                                   "error setting up signal pipe: {}",
+                                   err);
+                            Self::shutdown(create_cleanup, None);
 
-                    unsafe {
-                        SHUTDOWN_NOTIFY.write(Notify::new());
-                    }
+                            return;
+                        }
+                    };
 
                     match unsafe { signal(SIGTERM, handler as sighandler_t) } {
                         0 => {}
@@ -381,14 +797,55 @@ pub trait Standalone: Sized {
 
                     match app.run() {
                         Ok(run_cleanup) => {
-                            if unsafe {
-                                SHUTDOWN_NOTIFY
-                                    .assume_init_mut()
-                                    .wait_no_reset()
-                                    .is_err()
-                            } {
-                                error!(target: "standalone",
-                                       "bad condition variable")
+                            loop {
+                                let sig = read_signal(read_fd);
+
+                                if sig == SIGHUP {
+                                    info!(target: "standalone",
+                                          "reloading {} configuration",
+                                          Self::COMPONENT_NAME);
+
+                                    let config = Self::resolve_config(
+                                        &dirs,
+                                        config_path.as_deref()
+                                    );
+
+                                    Self::log_setup(
+                                        &dirs,
+                                        &handle,
+                                        config.as_ref(),
+                                        level_override,
+                                        true
+                                    );
+
+                                    match config {
+                                        Some(config) => app.reload(config),
+                                        None => {
+                                            error!(target: "standalone",
+                                                   "could not obtain valid \
+                                                    configuration for reload")
+                                        }
+                                    }
+                                } else {
+                                    if sig == SIGINT {
+                                        // Shutdown is about to begin
+                                        // and may take a while (or
+                                        // hang); keep draining the
+                                        // signal pipe on a background
+                                        // thread so that a second
+                                        // `SIGINT` can still force an
+                                        // immediate exit while the
+                                        // main thread gets on with
+                                        // `shutdown`.
+                                        thread::spawn(move || {
+                                            while read_signal(read_fd) != SIGINT {}
+
+                                            exit(1);
+                                        });
+                                    }
+
+                                    break;
+                                }
                             }
 
                             Self::shutdown(create_cleanup, Some(run_cleanup));
@@ -420,22 +877,366 @@ pub trait Standalone: Sized {
     }
 }
 
-static mut SHUTDOWN_NOTIFY: MaybeUninit<Notify> = MaybeUninit::uninit();
-static mut SHUTDOWN_ON_INT: bool = false;
+/// Write end of the self-pipe used to carry signal numbers out of
+/// the handler.
+///
+/// This is the only piece of global state the signal subsystem
+/// needs.  It is set once by `setup_signal_pipe`, before any handler
+/// is registered; after that `handler` only reads it, to pass it to
+/// `write`, which is itself async-signal-safe.
+static mut SIGNAL_WRITE_FD: c_int = -1;
+
+/// Create the self-pipe used to carry signal numbers off of the
+/// signal handler and onto the main thread.
+///
+/// Returns the read end of the pipe; the write end is stashed in
+/// `SIGNAL_WRITE_FD` for `handler` to use. Only the write end is set
+/// non-blocking, so that a full pipe (which would require many
+/// back-to-back unhandled signals) causes `write` to drop the byte
+/// rather than block inside the handler. The read end is left
+/// blocking so `read_signal` can sleep on it instead of spinning.
+fn setup_signal_pipe() -> io::Result<c_int> {
+    let mut fds: [c_int; 2] = [-1, -1];
+
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let write_fd = fds[1];
+    let flags = unsafe { libc::fcntl(write_fd, libc::F_GETFL) };
 
+    if flags < 0 ||
+        unsafe { libc::fcntl(write_fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    unsafe {
+        SIGNAL_WRITE_FD = write_fd;
+    }
+
+    Ok(fds[0])
+}
+
+/// The signal handler proper.
+///
+/// This does nothing but write the signal number to the self-pipe;
+/// everything else happens outside the handler, since none of that
+/// logic is async-signal-safe: the main loop reads each signal with
+/// `read_signal` and distinguishes reload from shutdown, and after a
+/// first `SIGINT` a background thread watches for a second one.
 unsafe extern "C" fn handler(sig: c_int) {
-    if sig == SIGINT {
-        if SHUTDOWN_ON_INT {
+    let byte = sig as u8;
+
+    libc::write(SIGNAL_WRITE_FD, &byte as *const u8 as *const c_void, 1);
+}
+
+/// Block until a signal number arrives on the self-pipe, returning
+/// it.
+///
+/// `read_fd` is blocking, so this sleeps until a handler writes a
+/// byte. A `read` returning `EINTR` (interrupted by a signal whose
+/// handler just wrote to the pipe) is retried rather than treated as
+/// an error. The write end never closes and a blocking `read` never
+/// returns `EAGAIN`/`EWOULDBLOCK`, so in practice none of this
+/// should happen; but a closed pipe (`read` returning `0`) or any
+/// other, non-retryable error means the signal subsystem can no
+/// longer do its job, so rather than spin retrying forever this
+/// gives up and exits instead.
+fn read_signal(read_fd: c_int) -> c_int {
+    loop {
+        let mut byte: u8 = 0;
+        let ret = unsafe {
+            libc::read(read_fd, &mut byte as *mut u8 as *mut c_void, 1)
+        };
+
+        if ret == 1 {
+            return c_int::from(byte);
+        }
+
+        if ret == 0 {
+            error!(target: "signal-handler",
+                   "signal pipe closed unexpectedly");
             exit(1);
+        }
+
+        let err = io::Error::last_os_error();
+
+        match err.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => continue,
+            _ => {
+                error!(target: "signal-handler",
+                       "error reading signal pipe: {}",
+                       err);
+                exit(1);
+            }
+        }
+    }
+}
+
+/// Deep-merge `overlay` into `base`.
+///
+/// Mappings are merged recursively, key by key; any other value
+/// (scalars, sequences, or a mapping overriding a non-mapping)
+/// simply replaces what was in `base`.
+fn merge_config_values(
+    base: &mut Value,
+    overlay: Value
+) {
+    match overlay {
+        Value::Mapping(overlay_map) => match base {
+            Value::Mapping(base_map) => {
+                for (key, value) in overlay_map {
+                    match base_map.get_mut(&key) {
+                        Some(existing) => merge_config_values(existing, value),
+                        None => {
+                            base_map.insert(key, value);
+                        }
+                    }
+                }
+            }
+            _ => *base = Value::Mapping(overlay_map)
+        },
+        other => *base = other
+    }
+}
+
+/// Environment variable suffix reserved by
+/// [Standalone::config_dirs] for selecting a component's
+/// configuration directory (e.g. `CONSTELLATION_GROUP_CONF_DIR`).
+///
+/// [env_override_segments] skips this exact suffix so that this
+/// pre-existing, documented feature doesn't also get picked up by
+/// [Standalone::apply_env_overrides] as a bogus `conf_dir` config
+/// field.
+const CONF_DIR_ENV_SUFFIX: &str = "CONF_DIR";
+
+/// Compute the config-value path segments a single environment
+/// variable override should be applied at, given the
+/// component-specific `prefix` (see
+/// [Standalone::apply_env_overrides]).
+///
+/// Returns `None` if `key` doesn't start with `prefix`, if the
+/// remaining suffix is exactly [CONF_DIR_ENV_SUFFIX] (reserved for
+/// configuration directory selection), or if splitting on `__`
+/// yields any empty segment.
+fn env_override_segments(
+    prefix: &str,
+    key: &str
+) -> Option<Vec<String>> {
+    let path = key.strip_prefix(prefix)?;
+
+    if path == CONF_DIR_ENV_SUFFIX {
+        return None;
+    }
+
+    let segments: Vec<String> =
+        path.split("__").map(|s| s.to_lowercase()).collect();
+
+    if segments.iter().any(|segment| segment.is_empty()) {
+        return None;
+    }
+
+    Some(segments)
+}
+
+/// Set the value at a dotted path within a `serde_yaml` tree,
+/// creating intermediate mappings as necessary.
+fn set_config_path(
+    root: &mut Value,
+    segments: &[String],
+    scalar: Value
+) {
+    let (head, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return
+    };
+
+    if !matches!(root, Value::Mapping(_)) {
+        *root = Value::Mapping(Mapping::new());
+    }
+
+    if let Value::Mapping(map) = root {
+        let key = Value::String(head.clone());
+
+        if rest.is_empty() {
+            map.insert(key, scalar);
         } else {
-            SHUTDOWN_ON_INT = true
+            let child = map
+                .entry(key)
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+            set_config_path(child, rest, scalar);
         }
     }
+}
 
-    if let Err(err) = SHUTDOWN_NOTIFY.assume_init_mut().notify() {
-        error!(target: "signal-handler",
-               "error sending shutdown notification: {}",
-               err);
+/// Compute the root log level override requested by `-v`/`--verbose`
+/// and `-q`/`--quiet`, if either was given.
+///
+/// Each `-v` raises the default `Info` level by one step; `-q`
+/// lowers it by one step.  Returns `None` if neither flag was given,
+/// so that the file or built-in logging configuration's own level is
+/// left untouched.
+fn cli_level_override(matches: &ArgMatches) -> Option<LevelFilter> {
+    let verbose = matches.get_count("verbose") as i64;
+    let quiet = matches.get_flag("quiet");
+
+    if verbose == 0 && !quiet {
+        return None;
+    }
+
+    let shift = verbose - i64::from(quiet);
+
+    Some(shift_level(LevelFilter::Info, shift))
+}
+
+/// Shift `level` up or down by `shift` steps along
+/// `Off, Error, Warn, Info, Debug, Trace`, clamping at either end.
+fn shift_level(
+    level: LevelFilter,
+    shift: i64
+) -> LevelFilter {
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace
+    ];
+
+    let idx = LEVELS
+        .iter()
+        .position(|candidate| *candidate == level)
+        .unwrap_or(3);
+    let shifted =
+        (idx as i64 + shift).clamp(0, LEVELS.len() as i64 - 1);
+
+    LEVELS[shifted as usize]
+}
+
+/// Synthesize a `log4rs` [Config] from a built-in [LoggingConfig],
+/// rather than parsing one from a YAML file.
+///
+/// `level_override`, when present, replaces whatever level
+/// `logging` specified (used to apply `-v`/`-q` from the command
+/// line).
+///
+/// `reload` is `true` when re-synthesizing the configuration for an
+/// already-running component (in response to `SIGHUP`) rather than
+/// setting it up for the first time. On reload, [File](LoggingConfig::File)
+/// and [Json](LoggingConfig::Json) targets are always reopened in
+/// append mode and skip the [IfExists] check entirely: the target
+/// legitimately already exists (this same process created it), and
+/// re-validating `Fail` or re-applying `Truncate` on every reload
+/// would defeat the log file chosen at first setup.
+fn build_logging_config(
+    logging: LoggingConfig,
+    level_override: Option<LevelFilter>,
+    reload: bool
+) -> Result<Config, String> {
+    match logging {
+        LoggingConfig::StderrTerminal { level } => {
+            let level = level_override.unwrap_or(level);
+            let appender =
+                ConsoleAppender::builder().target(Target::Stderr).build();
+
+            Config::builder()
+                .appender(
+                    Appender::builder().build("stderr", Box::new(appender))
+                )
+                .build(Root::builder().appender("stderr").build(level))
+                .map_err(|err| err.to_string())
+        }
+        LoggingConfig::File {
+            level,
+            path,
+            if_exists
+        } => {
+            let level = level_override.unwrap_or(level);
+
+            if !reload {
+                check_if_exists(&path, if_exists)?;
+            }
+
+            let appender = FileAppender::builder()
+                .append(reload || if_exists == IfExists::Append)
+                .build(&path)
+                .map_err(|err| err.to_string())?;
+
+            Config::builder()
+                .appender(Appender::builder().build("file", Box::new(appender)))
+                .build(Root::builder().appender("file").build(level))
+                .map_err(|err| err.to_string())
+        }
+        LoggingConfig::Json {
+            level,
+            path,
+            if_exists
+        } => {
+            let level = level_override.unwrap_or(level);
+
+            if !reload {
+                check_if_exists(&path, if_exists)?;
+            }
+
+            let appender = FileAppender::builder()
+                .append(reload || if_exists == IfExists::Append)
+                .encoder(Box::new(JsonLineEncoder))
+                .build(&path)
+                .map_err(|err| err.to_string())?;
+
+            Config::builder()
+                .appender(Appender::builder().build("json", Box::new(appender)))
+                .build(Root::builder().appender("json").build(level))
+                .map_err(|err| err.to_string())
+        }
+    }
+}
+
+/// Honor [IfExists::Fail] by rejecting a target path that already
+/// exists, before any appender gets a chance to open (and possibly
+/// truncate) it.
+///
+/// Only called from [build_logging_config] on first setup; a
+/// reload skips this check entirely (see there).
+fn check_if_exists(
+    path: &Path,
+    if_exists: IfExists
+) -> Result<(), String> {
+    if if_exists == IfExists::Fail && path.is_file() {
+        return Err(format!(
+            "log file {} already exists",
+            path.to_string_lossy()
+        ));
+    }
+
+    Ok(())
+}
+
+/// A minimal Bunyan-style JSON-line log encoder, used by
+/// [LoggingConfig::Json].
+#[derive(Debug)]
+struct JsonLineEncoder;
+
+impl Encode for JsonLineEncoder {
+    fn encode(
+        &self,
+        w: &mut dyn EncodeWrite,
+        record: &Record
+    ) -> anyhow::Result<()> {
+        let line = serde_json::json!({
+            "v": 0,
+            "time": chrono::Utc::now().to_rfc3339(),
+            "level": record.level().to_string(),
+            "target": record.target(),
+            "msg": record.args().to_string()
+        });
+
+        writeln!(w, "{}", line)?;
+
+        Ok(())
     }
 }
 
@@ -464,17 +1265,13 @@ fn report_signal_error(err: usize) {
     }
 }
 
-fn bootstrap_log_setup() -> Handle {
+fn bootstrap_log_setup(level: LevelFilter) -> Handle {
     // Set up an initial logger.  This will be used to report any
     // errors loading the configuration.
     let console = ConsoleAppender::builder().build();
     let log_config = match Config::builder()
         .appender(Appender::builder().build("console", Box::new(console)))
-        .build(
-            Root::builder()
-                .appender("console")
-                .build(LevelFilter::Trace)
-        ) {
+        .build(Root::builder().appender("console").build(level)) {
         Ok(log_config) => log_config,
         Err(err) => {
             panic!("Error initializing bootstrap logger: {}", err);
@@ -493,3 +1290,112 @@ fn bootstrap_log_setup() -> Handle {
 
     handle
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    /// Build a path under the system temp directory that's unique to
+    /// this test run, so tests touching the filesystem don't collide
+    /// with each other or with a previous run.
+    fn unique_temp_path(name: &str) -> PathBuf {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        std::env::temp_dir().join(format!(
+            "constellation-standalone-test-{}-{}-{}",
+            std::process::id(),
+            id,
+            name
+        ))
+    }
+
+    #[test]
+    fn reload_skips_fail_check_on_existing_file() {
+        let path = unique_temp_path("fail.log");
+
+        let logging = LoggingConfig::File {
+            level: LevelFilter::Info,
+            path: path.clone(),
+            if_exists: IfExists::Fail
+        };
+
+        assert!(build_logging_config(logging.clone(), None, false).is_ok());
+
+        // The file now exists; a reload must not re-run the `Fail`
+        // check against it, unlike a second first-time setup would.
+        assert!(build_logging_config(logging.clone(), None, false).is_err());
+        assert!(build_logging_config(logging, None, true).is_ok());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reload_does_not_truncate_existing_log() {
+        let path = unique_temp_path("truncate.log");
+
+        std::fs::write(&path, b"existing line\n").unwrap();
+
+        let logging = LoggingConfig::File {
+            level: LevelFilter::Info,
+            path: path.clone(),
+            if_exists: IfExists::Truncate
+        };
+
+        assert!(build_logging_config(logging, None, true).is_ok());
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+
+        assert_eq!(contents, "existing line\n");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn env_override_segments_reserves_conf_dir() {
+        let prefix = "CONSTELLATION_GROUP_";
+
+        assert_eq!(
+            env_override_segments(prefix, "CONSTELLATION_GROUP_CONF_DIR"),
+            None
+        );
+    }
+
+    #[test]
+    fn env_override_segments_parses_nested_path() {
+        let prefix = "CONSTELLATION_GROUP_";
+
+        assert_eq!(
+            env_override_segments(
+                prefix,
+                "CONSTELLATION_GROUP_LISTEN__PORT"
+            ),
+            Some(vec!["listen".to_string(), "port".to_string()])
+        );
+    }
+
+    #[test]
+    fn env_override_segments_coexists_with_conf_dir_selection() {
+        // `CONF_DIR` (used by `config_dirs` to pick a configuration
+        // directory) and an ordinary field override share the same
+        // component prefix; only the latter should turn into a
+        // config-path edit.
+        let prefix = "CONSTELLATION_GROUP_";
+
+        assert_eq!(
+            env_override_segments(prefix, "CONSTELLATION_GROUP_CONF_DIR"),
+            None
+        );
+        assert_eq!(
+            env_override_segments(
+                prefix,
+                "CONSTELLATION_GROUP_LISTEN_PORT"
+            ),
+            Some(vec!["listen_port".to_string()])
+        );
+    }
+}